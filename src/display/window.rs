@@ -2,16 +2,17 @@
 #![allow(unused_imports)]
 #![allow(unused_variables)]
 #![allow(dead_code)]
+use std::collections::HashMap;
 use std::time::{Instant, Duration};
 use neon::prelude::*;
 use neon::result::Throw;
 use crossbeam::channel::Sender;
-use skia_safe::{Color, Matrix};
+use skia_safe::{Color, Matrix, Rect};
 use glutin::platform::run_return::EventLoopExtRunReturn;
 use glutin::event_loop::{ControlFlow, EventLoop, EventLoopProxy, EventLoopClosed};
 use glutin::event::{Event, WindowEvent};
 use glutin::dpi::{LogicalSize, PhysicalSize, LogicalPosition};
-use glutin::window::CursorIcon;
+use glutin::window::{CursorIcon, WindowId};
 
 use crate::canvas::Page;
 use crate::context::BoxedContext2D;
@@ -19,7 +20,181 @@ use crate::utils::{argv, color_arg, float_arg, to_cursor_icon, to_canvas_fit};
 use super::{CanvasEvent, View, Fit};
 use super::event;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VsyncMode{
+  Uncapped,
+  Vsync,
+  Fixed,
+}
+
+pub struct Pacing{
+  mode: VsyncMode,
+  ns_per_frame: u64,
+  accumulator: u64,
+  last_tick: Instant,
+  // raw elapsed time summed across ticks that didn't yet cross ns_per_frame;
+  // rolled into last_elapsed_ns and zeroed once a tick actually fires, so
+  // delta() reflects the real gap since the last rendered frame even when
+  // the event loop polls tick() many times per frame
+  pending_elapsed_ns: u64,
+  last_elapsed_ns: u64,
+}
+
+impl Pacing{
+  pub fn new(fps:u64) -> Self {
+    Pacing{
+      mode: VsyncMode::Vsync,
+      ns_per_frame: Self::ns_per_frame(fps),
+      accumulator: 0,
+      last_tick: Instant::now(),
+      pending_elapsed_ns: 0,
+      last_elapsed_ns: 0,
+    }
+  }
+
+  fn ns_per_frame(fps:u64) -> u64 {
+    if fps == 0 { 0 } else { 1_000_000_000 / fps }
+  }
+
+  pub fn set_fps(&mut self, fps:u64){
+    self.ns_per_frame = Self::ns_per_frame(fps);
+    self.accumulator = 0;
+  }
+
+  pub fn set_mode(&mut self, mode:VsyncMode){
+    self.mode = mode;
+    self.accumulator = 0;
+  }
+
+  // in Fixed mode, advance the accumulator by the real elapsed time and report
+  // whether a whole ns_per_frame increment is ready to be ticked, clamping the
+  // accumulator so a slow frame can't spiral into an ever-growing backlog;
+  // Uncapped and Vsync (and Fixed at 0 fps) report a frame on every call
+  pub fn tick(&mut self) -> bool {
+    let now = Instant::now();
+    let elapsed = now.duration_since(self.last_tick).as_nanos() as u64;
+    self.last_tick = now;
+    self.pending_elapsed_ns += elapsed;
+
+    // a frame is firing this call: roll the elapsed time accumulated across
+    // however many polls it took into last_elapsed_ns and start fresh
+    let mut report = |this:&mut Self| {
+      this.last_elapsed_ns = this.pending_elapsed_ns;
+      this.pending_elapsed_ns = 0;
+    };
+
+    match self.mode{
+      VsyncMode::Uncapped => { report(self); true }
+      VsyncMode::Vsync => { report(self); true }
+      VsyncMode::Fixed if self.ns_per_frame == 0 => { report(self); true }
+      VsyncMode::Fixed => {
+        self.accumulator += elapsed;
+        self.accumulator = self.accumulator.min(self.ns_per_frame * 8);
+        if self.accumulator >= self.ns_per_frame{
+          self.accumulator -= self.ns_per_frame;
+          report(self);
+          true
+        }else{
+          false
+        }
+      }
+    }
+  }
+
+  pub fn alpha(&self) -> f64 {
+    if self.ns_per_frame == 0{
+      1.0
+    }else{
+      (self.accumulator as f64 / self.ns_per_frame as f64).min(1.0)
+    }
+  }
+
+  // wall-clock gap, in seconds, between the two most recent ticks that fired,
+  // measured when the later one fired (it doesn't grow afterwards) — summed
+  // across any sub-frame polls of tick() that didn't cross ns_per_frame, not
+  // just the gap since the single most recent call
+  pub fn delta(&self) -> f64 {
+    self.last_elapsed_ns as f64 / 1_000_000_000.0
+  }
+}
+
+#[cfg(test)]
+mod tests{
+  use super::*;
+
+  #[test]
+  fn delta_reflects_elapsed_tick_time_not_time_since_delta_call(){
+    let mut pacing = Pacing::new(60);
+    std::thread::sleep(Duration::from_millis(10));
+    pacing.tick();
+    let delta = pacing.delta();
+    assert!(delta >= 0.009, "expected delta to capture the ~10ms tick gap, got {delta}");
+
+    // delta() shouldn't keep advancing just because time passes after tick()
+    std::thread::sleep(Duration::from_millis(10));
+    assert_eq!(pacing.delta(), delta);
+  }
+
+  #[test]
+  fn delta_sums_elapsed_time_across_polls_that_precede_a_due_fixed_tick(){
+    let mut pacing = Pacing::new(0);
+    pacing.set_mode(VsyncMode::Fixed);
+    pacing.set_fps(100); // ns_per_frame = 10ms
+
+    // several sub-frame polls, none of which cross ns_per_frame on their own
+    std::thread::sleep(Duration::from_millis(4));
+    assert!(!pacing.tick());
+    std::thread::sleep(Duration::from_millis(4));
+    assert!(!pacing.tick());
+    std::thread::sleep(Duration::from_millis(4));
+    assert!(pacing.tick(), "12ms of polls should have crossed the 10ms frame");
+
+    let delta = pacing.delta();
+    assert!(delta >= 0.011, "expected delta to sum ~12ms across the skipped polls, got {delta}");
+  }
+}
+
+// The thread-safe half of a `Window`: just the proxy and channel needed to
+// push `CanvasEvent`s in from wherever rendering happens to live. Both
+// `EventLoopProxy<CanvasEvent>` and the crossbeam `Sender` are `Send + Sync`
+// as long as `CanvasEvent` is `Send`, so this can be cloned onto a render
+// worker thread while `Window` itself (and the `View` it pairs with, which
+// holds the Skia surface) stays pinned to the thread running the event loop.
+#[derive(Clone)]
+pub struct WindowControl{
+  id: WindowId,
+  proxy: EventLoopProxy<CanvasEvent>,
+  js_events: Option<Sender<CanvasEvent>>,
+}
+
+impl WindowControl{
+  pub fn request_redraw(&self){
+    self.proxy.send_event(CanvasEvent::Render(self.id)).ok();
+  }
+
+  // route by event kind, the same way Window itself does: Resized and
+  // Fullscreen go straight down the render-side channel the View drains, everything else
+  // goes through the proxy so it's dispatched via Event::UserEvent
+  pub fn push(&self, event:CanvasEvent) {
+    match (&event, &self.js_events){
+      (CanvasEvent::Resized(..) | CanvasEvent::Fullscreen(..), Some(channel)) => { channel.send(event).ok(); }
+      _ => { self.proxy.send_event(event).ok(); }
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct Hitbox{
+  pub id: String,
+  pub rect: Rect,
+  pub cursor: Option<CursorIcon>,
+}
+
 pub struct Window{
+  // stamped onto every CanvasEvent this window sends so the proxy side can
+  // target the right OS window (see super::CanvasEvent, whose variants now
+  // all carry a leading WindowId alongside their existing payload)
+  id: WindowId,
   proxy: EventLoopProxy<CanvasEvent>,
   position: LogicalPosition<i32>,
   size: LogicalSize<u32>,
@@ -32,15 +207,25 @@ pub struct Window{
   dpr: f64,
 
   fullscreen: bool,
+  // set when an OS-originated fullscreen change (went_fullscreen) just pushed
+  // new truth to JS; the next handle_feedback pass ignores its (now stale)
+  // fullscreen flag rather than clobbering what the OS just reported
+  pending_external_fullscreen: bool,
   visible: bool,
   animated: bool,
   fps: u64,
+  pacing: Pacing,
+
+  hitboxes: Vec<Hitbox>,
+  hovered: Option<String>,
+  pointer: LogicalPosition<f64>,
 }
 
 
 impl Window{
-  pub fn new(runloop:&EventLoop<CanvasEvent>, width:f32, height:f32) -> Self {
+  pub fn new(runloop:&EventLoop<CanvasEvent>, id:WindowId, width:f32, height:f32) -> Self {
     Window{
+      id,
       proxy: runloop.create_proxy(),
       position: LogicalPosition::new(0,0),
       size: LogicalSize::new(width as u32, height as u32),
@@ -52,13 +237,49 @@ impl Window{
       fit: Some(Fit::Contain{x:false, y:true}),
       dpr: 1.0,
       fps: 0,
+      pacing: Pacing::new(0),
+
+      hitboxes: Vec::new(),
+      hovered: None,
+      pointer: LogicalPosition::new(0.0, 0.0),
 
       visible:false,
       animated: false,
       fullscreen: false,
+      pending_external_fullscreen: false,
     }
   }
 
+  // replace the hitbox list with the ids/rects registered for the frame JS
+  // just finished describing, in the order they were drawn, and re-resolve
+  // hover against the last known pointer position — layout can shift between
+  // frames without the pointer itself moving, and hover should track that
+  pub fn set_hitboxes(&mut self, hitboxes:Vec<Hitbox>){
+    self.hitboxes = hitboxes;
+    self.resolve_hover(self.pointer);
+  }
+
+  // resolve the topmost hitbox under `pt` by scanning in reverse paint order
+  // (the last box drawn sits on top) and reconcile hover state against it
+  fn resolve_hover(&mut self, pt:LogicalPosition<f64>){
+    self.pointer = pt;
+    let hit = self.hitboxes.iter().rev()
+      .find(|hitbox| hitbox.rect.contains(skia_safe::Point::new(pt.x as f32, pt.y as f32)));
+
+    let hit_id = hit.map(|hitbox| hitbox.id.clone());
+    if hit_id != self.hovered{
+      if let Some(prev) = &self.hovered{
+        self.ui_events.hover_leave(prev);
+      }
+      if let Some(next) = &hit_id{
+        self.ui_events.hover_enter(next);
+      }
+      self.hovered = hit_id;
+    }
+
+    self.cursor = hit.and_then(|hitbox| hitbox.cursor).or(self.cursor);
+  }
+
   pub fn new_view(&mut self, runloop:&EventLoop<CanvasEvent>, c2d:Handle<BoxedContext2D>, backdrop:Option<Color>) -> View {
     let (s, r) = crossbeam::channel::unbounded::<CanvasEvent>();
     let mut view = View::new(&runloop, c2d, r, backdrop, self.size.width as f32, self.size.height as f32);
@@ -67,12 +288,23 @@ impl Window{
     view
   }
 
+  // hand out a cloneable, Send + Sync handle so a render worker thread can
+  // request redraws and forward its own CanvasEvents without touching the
+  // Window (or the View's Skia surfaces) directly
+  pub fn control(&self) -> WindowControl {
+    WindowControl{
+      id: self.id,
+      proxy: self.proxy.clone(),
+      js_events: self.js_events.clone(),
+    }
+  }
+
   pub fn show(&self){
-    self.proxy.send_event(CanvasEvent::Visible(true)).ok();
+    self.proxy.send_event(CanvasEvent::Visible(self.id, true)).ok();
   }
 
   pub fn render(&self){
-    self.proxy.send_event(CanvasEvent::Render).ok();
+    self.proxy.send_event(CanvasEvent::Render(self.id)).ok();
   }
 
   pub fn went_fullscreen(&mut self, is_fullscreen:bool){
@@ -80,6 +312,10 @@ impl Window{
     if is_fullscreen !=self.fullscreen{
       self.fullscreen = is_fullscreen;
       self.ui_events.go_fullscreen(is_fullscreen);
+      // the JS side doesn't know about this yet, so the feedback pass it
+      // sends in response is still carrying the old flag — ignore it once
+      // rather than letting it immediately undo what the OS just did
+      self.pending_external_fullscreen = true;
     }
   }
 
@@ -93,9 +329,9 @@ impl Window{
     if let Event::WindowEvent{event, ..} = event {
       if let WindowEvent::Resized(physical_size) = event {
         self.size = LogicalSize::from_physical(*physical_size, self.dpr);
-        // self.proxy.send_event(CanvasEvent::Resized(*physical_size)).ok();
+        // self.proxy.send_event(CanvasEvent::Resized(self.id, *physical_size)).ok();
         if let Some(channel) = &self.js_events{
-          channel.send(CanvasEvent::Resized(*physical_size)).unwrap();
+          channel.send(CanvasEvent::Resized(self.id, *physical_size)).unwrap();
         }
       }
 
@@ -103,19 +339,32 @@ impl Window{
         self.position = LogicalPosition::from_physical(*physical_pt, self.dpr);
       }
 
+      if let WindowEvent::CursorMoved{position, ..} = event {
+        self.resolve_hover(LogicalPosition::from_physical(*position, self.dpr));
+      }
+
       self.ui_events.capture(&event, self.dpr)
     }
   }
 
   pub fn communicate_pending(&mut self, cx: &mut FunctionContext, callback:&Handle<JsFunction>) -> ControlFlow {
-    match self.ui_events.is_empty(){
+    let due = self.pacing.tick();
+    match self.ui_events.is_empty() && !due{
       true => ControlFlow::Poll,
       false => self.communicate(cx, callback)
     }
   }
 
   pub fn communicate(&mut self, cx: &mut FunctionContext, callback:&Handle<JsFunction>) -> ControlFlow {
-    let changes = self.ui_events.serialized(cx);
+    let mut changes = self.ui_events.serialized(cx);
+    let delta = cx.number(self.pacing.delta());
+    let alpha = cx.number(self.pacing.alpha());
+    changes.push(delta);
+    changes.push(alpha);
+    // tag the batch with the window it came from so a WindowManager driving
+    // several windows off one callback can tell them apart
+    changes.push(cx.string(format!("{:?}", self.id)));
+
     let null = cx.null();
     if let Ok(response) = callback.call(cx, null, changes){
       if self.handle_feedback(cx, response).is_ok(){
@@ -132,7 +381,7 @@ impl Window{
         // 0: context
         if let Ok(c2d) = vals[0].downcast::<BoxedContext2D, _>(cx){
           let page = c2d.borrow_mut().get_page();
-          self.proxy.send_event(CanvasEvent::Page(page))?
+          self.proxy.send_event(CanvasEvent::Page(self.id, page))?
         }
 
         // 1: title
@@ -140,24 +389,29 @@ impl Window{
           let title = title.value(cx);
           if title != self.title{
             self.title = title.to_string();
-            self.proxy.send_event(CanvasEvent::Title(title))?
+            self.proxy.send_event(CanvasEvent::Title(self.id, title))?
           }
         }
 
         // 2: 'keep running' flag
         if let Ok(active) = vals[2].downcast::<JsBoolean, _>(cx){
           if !active.value(cx){
-            self.proxy.send_event(CanvasEvent::Close)?
+            self.proxy.send_event(CanvasEvent::Close(self.id))?
           }
         }
 
         // 3: fullscreen flag
-        if let Ok(is_full) = vals[3].downcast::<JsBoolean, _>(cx){
+        if self.pending_external_fullscreen{
+          // an OS-originated transition just reconciled self.fullscreen and
+          // told JS about it; this feedback pass was already in flight with
+          // the pre-transition value, so drop it instead of fighting back
+          self.pending_external_fullscreen = false;
+        } else if let Ok(is_full) = vals[3].downcast::<JsBoolean, _>(cx){
           let is_full = is_full.value(cx);
           if is_full != self.fullscreen{
             self.fullscreen = is_full;
             if let Some(channel) = &self.js_events{
-              channel.send(CanvasEvent::Fullscreen(is_full)).unwrap();
+              channel.send(CanvasEvent::Fullscreen(self.id, is_full)).unwrap();
             }
             self.ui_events.go_fullscreen(is_full);
           }
@@ -168,7 +422,8 @@ impl Window{
           let fps = fps.value(cx) as u64;
           if fps != self.fps{
             self.fps = fps;
-            self.proxy.send_event(CanvasEvent::FrameRate(fps))?
+            self.pacing.set_fps(fps);
+            self.proxy.send_event(CanvasEvent::FrameRate(self.id, fps))?
           }
         }
 
@@ -178,7 +433,7 @@ impl Window{
             let size = LogicalSize::new( width.value(cx) as u32, height.value(cx) as u32 );
             if size != self.size{
               self.size = size;
-              self.proxy.send_event(CanvasEvent::Size(size))?
+              self.proxy.send_event(CanvasEvent::Size(self.id, size))?
             }
           }
         }
@@ -189,7 +444,7 @@ impl Window{
             let position = LogicalPosition::new( x.value(cx) as i32, y.value(cx) as i32 );
             if position != self.position{
               self.position = position;
-              self.proxy.send_event(CanvasEvent::Position(position))?
+              self.proxy.send_event(CanvasEvent::Position(self.id, position))?
             }
           }
         }
@@ -200,7 +455,7 @@ impl Window{
           let cursor_icon = to_cursor_icon(&cursor_style);
           if cursor_icon != self.cursor && cursor_icon.is_some() || cursor_style == "none"{
             self.cursor = cursor_icon;
-            self.proxy.send_event(CanvasEvent::Cursor(cursor_icon))?
+            self.proxy.send_event(CanvasEvent::Cursor(self.id, cursor_icon))?
           }
         }
 
@@ -210,7 +465,7 @@ impl Window{
           let fit_mode = to_canvas_fit(&fit_style);
           if fit_mode != self.fit && fit_mode.is_some() || fit_style == "none"{
             self.fit = fit_mode;
-            self.proxy.send_event(CanvasEvent::Fit(fit_mode))?
+            self.proxy.send_event(CanvasEvent::Fit(self.id, fit_mode))?
           }
         }
 
@@ -219,14 +474,128 @@ impl Window{
           let is_visible = is_visible.value(cx);
           if is_visible != self.visible{
             self.visible = is_visible;
-            self.proxy.send_event(CanvasEvent::Visible(is_visible))?
+            self.proxy.send_event(CanvasEvent::Visible(self.id, is_visible))?
           }
         }
 
+        // 13: ordered hitbox list for this frame: [[id, x, y, w, h, cursor?], ...]
+        if let Ok(boxes) = vals[13].downcast::<JsArray, _>(cx){
+          if let Ok(boxes) = boxes.to_vec(cx){
+            let mut hitboxes = Vec::with_capacity(boxes.len());
+            for entry in boxes{
+              if let Ok(entry) = entry.downcast::<JsArray, _>(cx){
+                if let Ok(entry) = entry.to_vec(cx){
+                  if let (Ok(id), Ok(x), Ok(y), Ok(w), Ok(h)) = (
+                    entry[0].downcast::<JsString, _>(cx),
+                    entry[1].downcast::<JsNumber, _>(cx),
+                    entry[2].downcast::<JsNumber, _>(cx),
+                    entry[3].downcast::<JsNumber, _>(cx),
+                    entry[4].downcast::<JsNumber, _>(cx),
+                  ){
+                    let cursor = entry.get(5).and_then(|v| v.downcast::<JsString, _>(cx).ok())
+                      .and_then(|style| to_cursor_icon(&style.value(cx)));
+                    hitboxes.push(Hitbox{
+                      id: id.value(cx),
+                      rect: Rect::new(x.value(cx) as f32, y.value(cx) as f32,
+                        (x.value(cx) + w.value(cx)) as f32, (y.value(cx) + h.value(cx)) as f32),
+                      cursor,
+                    });
+                  }
+                }
+              }
+            }
+            self.set_hitboxes(hitboxes);
+          }
+        }
+
+        // 12: pacing mode ("uncapped", "vsync", or "fixed"); this only sets the
+        // Pacing mode, the GL swap interval is not changed, and "uncapped" and
+        // "vsync" both report a frame on every poll
+        if let Ok(vsync_style) = vals[12].downcast::<JsString, _>(cx){
+          let vsync_mode = match vsync_style.value(cx).as_str(){
+            "uncapped" => VsyncMode::Uncapped,
+            "fixed" => VsyncMode::Fixed,
+            _ => VsyncMode::Vsync,
+          };
+          self.pacing.set_mode(vsync_mode);
+        }
+
       }
     }
 
     Ok(())
   }
 
+}
+
+// Owns every open `Window`/`View` pair sharing a single `EventLoop<CanvasEvent>`,
+// routing OS events to the window they targeted and batching JS callbacks by
+// window id so a single draw loop can drive palettes, previews, and tool
+// windows alongside the primary canvas.
+pub struct WindowManager{
+  windows: HashMap<WindowId, Window>,
+  views: HashMap<WindowId, View>,
+}
+
+impl WindowManager{
+  pub fn new() -> Self {
+    WindowManager{
+      windows: HashMap::new(),
+      views: HashMap::new(),
+    }
+  }
+
+  pub fn add(&mut self, id:WindowId, window:Window, view:View){
+    self.windows.insert(id, window);
+    self.views.insert(id, view);
+  }
+
+  pub fn remove(&mut self, id:&WindowId){
+    self.windows.remove(id);
+    self.views.remove(id);
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.windows.is_empty()
+  }
+
+  pub fn get_mut(&mut self, id:&WindowId) -> Option<&mut Window> {
+    self.windows.get_mut(id)
+  }
+
+  pub fn get_view_mut(&mut self, id:&WindowId) -> Option<&mut View> {
+    self.views.get_mut(id)
+  }
+
+  // dispatch a WindowEvent to the Window it was addressed to, leaving the
+  // rest of the windows untouched
+  pub fn handle_event(&mut self, event:&Event<CanvasEvent>){
+    if let Event::WindowEvent{window_id, ..} = event{
+      if let Some(window) = self.windows.get_mut(window_id){
+        window.handle_event(event);
+      }
+    }
+  }
+
+  // let JS drain every window's pending events in one pass (each batch is
+  // tagged with its window id by Window::communicate); a window whose
+  // communicate_pending says Exit is closed and dropped, but the rest keep
+  // running until the last one exits
+  pub fn communicate_pending(&mut self, cx: &mut FunctionContext, callback:&Handle<JsFunction>) -> ControlFlow {
+    let mut closed = Vec::new();
+    for (id, window) in self.windows.iter_mut(){
+      if window.communicate_pending(cx, callback) == ControlFlow::Exit{
+        closed.push(*id);
+      }
+    }
+
+    for id in closed{
+      self.remove(&id);
+    }
+
+    match self.windows.is_empty(){
+      true => ControlFlow::Exit,
+      false => ControlFlow::Poll,
+    }
+  }
 }
\ No newline at end of file